@@ -0,0 +1,181 @@
+//! Async driver, gated behind the `async` feature. Identical to
+//! [`crate::blocking`] except that the oscillator-stabilization delays are
+//! awaited through a generic [`DelayUs`] implementation instead of a
+//! hard-coded runtime, so callers can plug in whatever executor they use.
+
+use crate::common::Core;
+use crate::{mode1, Error, PRESCALE_MAX, PRESCALE_MIN, PRE_SCALE, MODE1};
+use core::future::Future;
+use core::ops::{Deref, DerefMut};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use log::{debug, info};
+
+/// An async microsecond delay, independent of any particular executor.
+///
+/// Written with an explicit `-> impl Future` return instead of `async fn`
+/// so the trait doesn't trip `clippy::async_fn_in_trait`'s `Send`-bound
+/// warning; this driver only ever calls it from single-threaded contexts.
+pub trait DelayUs {
+    /// Waits for at least `us` microseconds.
+    fn delay_us(&mut self, us: u32) -> impl Future<Output = ()>;
+}
+
+/// A Representation of a PCA9685 Chip
+pub struct PCA9685<I2C, D> {
+    core: Core<I2C>,
+    delay: D,
+}
+
+impl<I2C, D> Deref for PCA9685<I2C, D> {
+    type Target = Core<I2C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.core
+    }
+}
+
+impl<I2C, D> DerefMut for PCA9685<I2C, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.core
+    }
+}
+
+impl<I2C, D, E> PCA9685<I2C, D>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    D: DelayUs,
+{
+
+    /// Creates a new PCA9865
+    pub fn new(address: u8, bus: I2C, delay: D) -> Result<PCA9685<I2C, D>, Error<E>> {
+        let dev = PCA9685 {
+            core: Core::new(address, bus),
+            delay,
+        };
+        Ok(dev)
+    }
+
+    /// Start the PCA9865.
+    /// The chip needs a little time to start.
+    pub async fn start(&mut self) -> Result<(), Error<E>> {
+        info!(target: "PCA9685_events", "Starting chip");
+
+        //Read Mode 1
+        let mut mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut mode)?;
+        let mode = mode[0];
+        debug!(target: "PCA9685_events", "Current mode {:#b}", mode);
+
+        //Clear Sleep bit
+        debug!(target: "PCA9685_events", "Writing to mode 1: {:#b}", mode-mode1::SLEEP);
+        self.core.bus.write(self.core.address, &[MODE1, mode - mode1::SLEEP])?;
+
+        //Wait for at least 500us, stabilize oscillator
+        self.delay.delay_us(500).await;
+
+        // Write a logic 1 to bit 7 to clear, if needed, and set the
+        // Auto-Increment bit so multi-register writes can be sent as a
+        // single contiguous transaction
+        self.core.bus.write(self.core.address, &[MODE1, (mode - mode1::SLEEP) | mode1::AI])?;
+
+        //Debug Check the Mode
+        let mut debug_mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut debug_mode)?;
+        debug!(target: "PCA9685_events", "Mode: {:#b}", debug_mode[0]);
+        info!(target: "PCA9685_events", "Started Chip!");
+        Ok(())
+    }
+
+    /// Restart all PWM channels that were active when the chip went to
+    /// sleep, resuming from where they left off, per the datasheet RESTART
+    /// sequence. Does nothing if the RESTART bit is not set.
+    pub async fn restart(&mut self) -> Result<(), Error<E>> {
+        let mut mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut mode)?;
+        let mode = mode[0];
+        debug!(target: "PCA9685_events", "Current mode {:#b}", mode);
+
+        if mode & mode1::RESTART != 0 {
+            info!(target: "PCA9685_events", "Restarting channels");
+
+            //Clear the sleep bit so the oscillator can stabilize again
+            self.core.bus.write(self.core.address, &[MODE1, mode & !mode1::SLEEP])?;
+
+            //Wait for at least 500us, stabilize oscillator
+            self.delay.delay_us(500).await;
+
+            //Write logic 1 to bit 7 to restart the previously active channels
+            self.core.bus.write(self.core.address, &[MODE1, (mode & !mode1::SLEEP) | mode1::RESTART])?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the prescale value from a given frequency
+    /// # Warnings
+    /// - In order to change the prescale, the chip must be put into sleep.
+    ///   Make sure that anything important be safetied before use.
+    ///
+    /// - This function tries to be as close as possible to the given frequency.
+    pub async fn set_prescale_fr(&mut self, frequency: u16) -> Result<(), Error<E>> {
+        //Get the old prescale first so we can skip reprogramming entirely if
+        //it wouldn't change, mirroring the Linux atomic-API fix
+        let mut prescale_buf = [0];
+        self.core.bus.write_read(self.core.address, &[PRE_SCALE], &mut prescale_buf)?;
+        let old_prescale = prescale_buf[0];
+        debug!(target: "PCA9685_events", "Old Prescale is {:#X}", old_prescale);
+
+        //Get the new prescale
+        let prescale_val = crate::prescale_from_freq(self.core.oscillator_freq, frequency)
+            .clamp(PRESCALE_MIN, PRESCALE_MAX);
+
+        if prescale_val == old_prescale {
+            debug!(target: "PCA9685_events", "Prescale unchanged, skipping reprogramming");
+            self.core.update_timing(old_prescale);
+            return Ok(());
+        }
+
+        self.sleep()?;
+
+        //Set the Prescale
+        self.core.bus.write_read(self.core.address, &[PRE_SCALE, prescale_val], &mut prescale_buf)?;
+        info!(target: "PCA9685_events","New Prescale is {:#X}", prescale_buf[0]);
+
+        //Start the chip again
+        self.start().await?;
+
+        self.core.update_timing(prescale_val);
+
+        Ok(())
+    }
+
+    /// Set to use External Clock
+    /// # Warnings
+    /// - In order to use the EXTCLK pin, the chip must be put to sleep.
+    /// - In order to reset this mode, you have to run a power cycle (or software reset).
+    /// - Max frequency is 50 Mhz
+    /// - **Untested**
+    pub async fn set_external_clock(&mut self, clock_speed: u32) -> Result<(), Error<E>> {
+        // Go to sleep
+        self.sleep()?;
+
+        //Get the current mode
+        let mut mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut mode)?;
+        let mode = mode[0];
+        debug!(target: "PCA9685_events", "Current mode {:#b}", mode);
+
+        //Write logic 1 to sleep & EXTCLK,
+        self.core.bus.write(self.core.address, &[MODE1, mode + mode1::EXTCLK])?;
+
+        //Wake up
+        self.start().await?;
+
+        self.core.oscillator_freq = clock_speed;
+
+        let prescale = self.read_prescale()?;
+        self.core.update_timing(prescale);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,220 @@
+//! Blocking driver, usable without an async runtime. The crate is not
+//! `no_std`: it links `std`, which it uses for `f32` rounding.
+
+use crate::common::Core;
+use crate::{mode1, Error, PRESCALE_MAX, PRESCALE_MIN, PRE_SCALE, MODE1};
+use core::ops::{Deref, DerefMut};
+use embedded_hal::blocking::delay::DelayUs;
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use log::{debug, info};
+
+/// A Representation of a PCA9685 Chip
+pub struct PCA9685<I2C, D> {
+    core: Core<I2C>,
+    delay: D,
+}
+
+impl<I2C, D> Deref for PCA9685<I2C, D> {
+    type Target = Core<I2C>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.core
+    }
+}
+
+impl<I2C, D> DerefMut for PCA9685<I2C, D> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.core
+    }
+}
+
+impl<I2C, D, E> PCA9685<I2C, D>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+    D: DelayUs<u32>,
+{
+
+    /// Creates a new PCA9865
+    pub fn new(address: u8, bus: I2C, delay: D) -> Result<PCA9685<I2C, D>, Error<E>> {
+        let dev = PCA9685 {
+            core: Core::new(address, bus),
+            delay,
+        };
+        Ok(dev)
+    }
+
+    /// Start the PCA9865.
+    /// The chip needs a little time to start.
+    pub fn start(&mut self) -> Result<(), Error<E>> {
+        info!(target: "PCA9685_events", "Starting chip");
+
+        //Read Mode 1
+        let mut mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut mode)?;
+        let mode = mode[0];
+        debug!(target: "PCA9685_events", "Current mode {:#b}", mode);
+
+        //Clear Sleep bit
+        debug!(target: "PCA9685_events", "Writing to mode 1: {:#b}", mode-mode1::SLEEP);
+        self.core.bus.write(self.core.address, &[MODE1, mode - mode1::SLEEP])?;
+
+        //Wait for at least 500us, stabilize oscillator
+        self.delay.delay_us(500);
+
+        // Write a logic 1 to bit 7 to clear, if needed, and set the
+        // Auto-Increment bit so multi-register writes can be sent as a
+        // single contiguous transaction
+        self.core.bus.write(self.core.address, &[MODE1, (mode - mode1::SLEEP) | mode1::AI])?;
+
+        //Debug Check the Mode
+        let mut debug_mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut debug_mode)?;
+        debug!(target: "PCA9685_events", "Mode: {:#b}", debug_mode[0]);
+        info!(target: "PCA9685_events", "Started Chip!");
+        Ok(())
+    }
+
+    /// Restart all PWM channels that were active when the chip went to
+    /// sleep, resuming from where they left off, per the datasheet RESTART
+    /// sequence. Does nothing if the RESTART bit is not set.
+    pub fn restart(&mut self) -> Result<(), Error<E>> {
+        let mut mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut mode)?;
+        let mode = mode[0];
+        debug!(target: "PCA9685_events", "Current mode {:#b}", mode);
+
+        if mode & mode1::RESTART != 0 {
+            info!(target: "PCA9685_events", "Restarting channels");
+
+            //Clear the sleep bit so the oscillator can stabilize again
+            self.core.bus.write(self.core.address, &[MODE1, mode & !mode1::SLEEP])?;
+
+            //Wait for at least 500us, stabilize oscillator
+            self.delay.delay_us(500);
+
+            //Write logic 1 to bit 7 to restart the previously active channels
+            self.core.bus.write(self.core.address, &[MODE1, (mode & !mode1::SLEEP) | mode1::RESTART])?;
+        }
+
+        Ok(())
+    }
+
+    /// Set the prescale value from a given frequency
+    /// # Warnings
+    /// - In order to change the prescale, the chip must be put into sleep.
+    ///   Make sure that anything important be safetied before use.
+    ///
+    /// - This function tries to be as close as possible to the given frequency.
+    pub fn set_prescale_fr(&mut self, frequency: u16) -> Result<(), Error<E>> {
+        //Get the old prescale first so we can skip reprogramming entirely if
+        //it wouldn't change, mirroring the Linux atomic-API fix
+        let mut prescale_buf = [0];
+        self.core.bus.write_read(self.core.address, &[PRE_SCALE], &mut prescale_buf)?;
+        let old_prescale = prescale_buf[0];
+        debug!(target: "PCA9685_events", "Old Prescale is {:#X}", old_prescale);
+
+        //Get the new prescale
+        let prescale_val = crate::prescale_from_freq(self.core.oscillator_freq, frequency)
+            .clamp(PRESCALE_MIN, PRESCALE_MAX);
+
+        if prescale_val == old_prescale {
+            debug!(target: "PCA9685_events", "Prescale unchanged, skipping reprogramming");
+            self.core.update_timing(old_prescale);
+            return Ok(());
+        }
+
+        self.sleep()?;
+
+        //Set the Prescale
+        self.core.bus.write_read(self.core.address, &[PRE_SCALE, prescale_val], &mut prescale_buf)?;
+        info!(target: "PCA9685_events","New Prescale is {:#X}", prescale_buf[0]);
+
+        //Start the chip again
+        self.start()?;
+
+        self.core.update_timing(prescale_val);
+
+        Ok(())
+    }
+
+    /// Set to use External Clock
+    /// # Warnings
+    /// - In order to use the EXTCLK pin, the chip must be put to sleep.
+    /// - In order to reset this mode, you have to run a power cycle (or software reset).
+    /// - Max frequency is 50 Mhz
+    /// - **Untested**
+    pub fn set_external_clock(&mut self, clock_speed: u32) -> Result<(), Error<E>> {
+        // Go to sleep
+        self.sleep()?;
+
+        //Get the current mode
+        let mut mode = [0];
+        self.core.bus.write_read(self.core.address, &[MODE1], &mut mode)?;
+        let mode = mode[0];
+        debug!(target: "PCA9685_events", "Current mode {:#b}", mode);
+
+        //Write logic 1 to sleep & EXTCLK,
+        self.core.bus.write(self.core.address, &[MODE1, mode + mode1::EXTCLK])?;
+
+        //Wake up
+        self.start()?;
+
+        self.core.oscillator_freq = clock_speed;
+
+        let prescale = self.read_prescale()?;
+        self.core.update_timing(prescale);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PCA9685;
+    use crate::test_support::MockI2c;
+    use embedded_hal::blocking::delay::DelayUs;
+
+    /// Counts calls instead of actually waiting, so tests can assert
+    /// whether `start` was reached (`sleep` does not delay).
+    struct MockDelay {
+        calls: u32,
+    }
+
+    impl DelayUs<u32> for MockDelay {
+        fn delay_us(&mut self, _us: u32) {
+            self.calls += 1;
+        }
+    }
+
+    #[test]
+    fn set_prescale_fr_skips_reprogramming_when_unchanged() {
+        let mut bus = MockI2c::new();
+        // Pre-program the chip with the prescale that `set_prescale_fr(200)`
+        // would compute, so the call should be a no-op.
+        bus.regs[crate::PRE_SCALE as usize] = crate::prescale_from_freq(25_000_000, 200);
+        bus.regs[crate::MODE1 as usize] = 0xAA;
+        let mut dev = PCA9685::new(0x40, bus, MockDelay { calls: 0 }).unwrap();
+
+        dev.set_prescale_fr(200).unwrap();
+
+        assert_eq!(dev.delay.calls, 0, "skip path must not sleep/restart the chip");
+        assert_eq!(dev.core.bus.regs[crate::MODE1 as usize], 0xAA, "MODE1 must be untouched");
+        assert!(dev.core.period_us.is_some());
+    }
+
+    #[test]
+    fn set_prescale_fr_reprograms_when_changed() {
+        let mut bus = MockI2c::new();
+        bus.regs[crate::PRE_SCALE as usize] = 0;
+        bus.regs[crate::MODE1 as usize] = 0;
+        let mut dev = PCA9685::new(0x40, bus, MockDelay { calls: 0 }).unwrap();
+
+        dev.set_prescale_fr(200).unwrap();
+
+        assert!(dev.delay.calls > 0, "changed prescale must go through sleep/start");
+        assert_eq!(
+            dev.core.bus.regs[crate::PRE_SCALE as usize],
+            crate::prescale_from_freq(25_000_000, 200)
+        );
+    }
+}
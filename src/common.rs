@@ -0,0 +1,366 @@
+//! Delay-independent driver state and logic shared by [`crate::blocking`]
+//! and [`crate::asynchronous`]. Both wrap a [`Core`] and `Deref`/`DerefMut`
+//! to it, adding only the handful of methods that actually wait on the
+//! oscillator (`start`, `restart`, `set_prescale_fr`, `set_external_clock`).
+
+use crate::{
+    mode1, mode2, Error, ALL_LED_ON_L, FREQUENCY_OSCILLATOR, GENERAL_CALL_ADDRESS, LED0_OFF_H,
+    LED0_OFF_L, LED0_ON_H, LED0_ON_L, LED_FULL, MODE1, MODE2, PRE_SCALE, SWRST,
+};
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+use log::{debug, info};
+
+/// Shared state and register-level logic for a PCA9685, independent of
+/// whatever delay mechanism `blocking`/`asynchronous` layer on top.
+pub struct Core<I2C> {
+    pub(crate) address: u8,
+    pub(crate) bus: I2C,
+    pub(crate) oscillator_freq: u32,
+    /// Cached PWM period in microseconds for the currently programmed
+    /// prescale, used by the microsecond/percent duty-cycle helpers.
+    /// `None` until a prescale has been set.
+    pub(crate) period_us: Option<f32>,
+}
+
+impl<I2C, E> Core<I2C>
+where
+    I2C: Write<Error = E> + WriteRead<Error = E>,
+{
+    pub(crate) fn new(address: u8, bus: I2C) -> Self {
+        Core {
+            address,
+            bus,
+            oscillator_freq: FREQUENCY_OSCILLATOR,
+            period_us: None,
+        }
+    }
+
+    /// Recompute the cached PWM period from the given prescale and the
+    /// currently configured oscillator frequency.
+    pub(crate) fn update_timing(&mut self, prescale: u8) {
+        let actual_freq = self.oscillator_freq as f32 / (4096.0 * (prescale as f32 + 1.0));
+        self.period_us = Some(1_000_000.0 / actual_freq);
+    }
+
+    /// Issue a software reset over the I2C General Call address, recovering
+    /// a wedged chip (or exiting EXTCLK mode) without a physical power
+    /// cycle.
+    pub fn software_reset(&mut self) -> Result<(), Error<E>> {
+        info!(target: "PCA9685_events", "Issuing software reset");
+        self.bus.write(GENERAL_CALL_ADDRESS, &[SWRST])?;
+        Ok(())
+    }
+
+    /// Put the chip into sleep
+    pub fn sleep(&mut self) -> Result<(), Error<E>> {
+        info!(target: "PCA9685_events", "Going to sleep");
+        //Get the current mode
+        let mut mode = [0];
+        self.bus.write_read(self.address, &[MODE1], &mut mode)?;
+        let mode = mode[0];
+        debug!(target: "PCA9685_events", "Current mode {:#b}", mode);
+
+        //If chip is not in sleep
+        if mode & mode1::SLEEP == 0 {
+            //Go to sleep
+            let mut buf = [0];
+            let mode_sleep = mode + mode1::SLEEP;
+            debug!(target: "PCA9685_events", "Writing sleep mode {:#b}", mode);
+            self.bus.write_read(self.address, &[MODE1, mode_sleep], &mut buf)?;
+            debug!(target: "PCA9685_events", "Mode: {:#b}", buf[0]);
+        }
+        info!(target: "PCA9685_events","Put the chip to sleep!");
+        Ok(())
+    }
+
+    /// Reads the prescale directly from the chip.
+    pub fn read_prescale(&mut self) -> Result<u8, Error<E>> {
+        let mut prescale_buf = [0];
+        debug!(target: "PCA9685_events", "Reading prescale");
+        self.bus.write_read(self.address, &[PRE_SCALE], &mut prescale_buf)?;
+        let prescale = prescale_buf[0];
+        debug!(target: "PCA9685_events", "Prescale is {}", prescale);
+        Ok(prescale)
+    }
+
+    /// Set the "on" count for a channel, 0 - 4095.
+    /// Channels range from 0 - 15.
+    pub fn set_channel_on(&mut self, channel: u8, on: u16) -> Result<(), Error<E>> {
+        if channel >= 16 || on > 4095 {
+            return Err(Error::InvalidInputData);
+        }
+
+        let on_l = (on & 0xFF) as u8;
+        let on_h = (on >> 8) as u8;
+        self.bus.write(self.address, &[LED0_ON_L + 4*channel, on_l])?;
+        self.bus.write(self.address, &[LED0_ON_H + 4*channel, on_h])?;
+
+        Ok(())
+    }
+
+    /// Set the "off" count for a channel, 0 - 4095.
+    /// Channels range from 0 - 15.
+    pub fn set_channel_off(&mut self, channel: u8, off: u16) -> Result<(), Error<E>> {
+        if channel >= 16 || off > 4095 {
+            return Err(Error::InvalidInputData);
+        }
+
+        let off_l = (off & 0xFF) as u8;
+        let off_h = (off >> 8) as u8;
+        self.bus.write(self.address, &[LED0_OFF_L + 4*channel, off_l])?;
+        self.bus.write(self.address, &[LED0_OFF_H + 4*channel, off_h])?;
+
+        Ok(())
+    }
+
+    /// Set the pulse-widths for a channel.
+    /// Channels range from 0 - 15.
+    /// Since the device uses 12bit accuracy, `on` and `off` must each be
+    /// 0 - 4095.
+    ///
+    /// All four `LED0_ON_L..LED0_OFF_H` registers are written as a single
+    /// auto-incremented transaction, so the pulse can never be observed
+    /// half-updated.
+    pub fn set_channel_on_off(&mut self, channel: u8, on: u16, off: u16) -> Result<(), Error<E>> {
+        if channel >= 16 || on > 4095 || off > 4095 || (on == 0 && off == 0) {
+            return Err(Error::InvalidInputData);
+        }
+
+        let on_l = (on & 0xFF) as u8;
+        let on_h = (on >> 8) as u8;
+        let off_l = (off & 0xFF) as u8;
+        let off_h = (off >> 8) as u8;
+        self.bus.write(self.address, &[LED0_ON_L + 4*channel, on_l, on_h, off_l, off_h])?;
+
+        Ok(())
+    }
+
+    /// Set the pulse-widths for every channel at once via the `ALL_LED_*`
+    /// registers, in a single auto-incremented transaction.
+    pub fn set_all_channels_on_off(&mut self, on: u16, off: u16) -> Result<(), Error<E>> {
+        if on > 4095 || off > 4095 || (on == 0 && off == 0) {
+            return Err(Error::InvalidInputData);
+        }
+
+        let on_l = (on & 0xFF) as u8;
+        let on_h = (on >> 8) as u8;
+        let off_l = (off & 0xFF) as u8;
+        let off_h = (off >> 8) as u8;
+        self.bus.write(self.address, &[ALL_LED_ON_L, on_l, on_h, off_l, off_h])?;
+
+        Ok(())
+    }
+
+    /// Drive a channel fully high, bypassing the 12-bit counters.
+    /// Per the datasheet, full-OFF takes precedence over full-ON, so the
+    /// full-ON bit is set first and the full-OFF bit is cleared after it,
+    /// to avoid a glitch.
+    pub fn set_channel_full_on(&mut self, channel: u8) -> Result<(), Error<E>> {
+        if channel >= 16 {
+            return Err(Error::InvalidInputData);
+        }
+
+        //Set the full-ON bit first; clearing full-OFF before that would let
+        //the channel briefly follow whatever stale on/off counts are
+        //already sitting in the other registers
+        let mut on_h = [0];
+        self.bus.write_read(self.address, &[LED0_ON_H + 4*channel], &mut on_h)?;
+        self.bus.write(self.address, &[LED0_ON_H + 4*channel, on_h[0] | LED_FULL])?;
+
+        let mut off_h = [0];
+        self.bus.write_read(self.address, &[LED0_OFF_H + 4*channel], &mut off_h)?;
+        self.bus.write(self.address, &[LED0_OFF_H + 4*channel, off_h[0] & !LED_FULL])?;
+
+        Ok(())
+    }
+
+    /// Drive a channel fully low, bypassing the 12-bit counters.
+    pub fn set_channel_full_off(&mut self, channel: u8) -> Result<(), Error<E>> {
+        if channel >= 16 {
+            return Err(Error::InvalidInputData);
+        }
+
+        let mut off_h = [0];
+        self.bus.write_read(self.address, &[LED0_OFF_H + 4*channel], &mut off_h)?;
+        self.bus.write(self.address, &[LED0_OFF_H + 4*channel, off_h[0] | LED_FULL])?;
+
+        Ok(())
+    }
+
+    /// Disable a channel's output by setting the full-OFF bit, without
+    /// touching the ON/OFF counters, so the previously configured duty
+    /// cycle is restored when the channel is re-enabled with `enable`.
+    pub fn disable(&mut self, channel: u8) -> Result<(), Error<E>> {
+        if channel >= 16 {
+            return Err(Error::InvalidInputData);
+        }
+
+        let mut off_h = [0];
+        self.bus.write_read(self.address, &[LED0_OFF_H + 4*channel], &mut off_h)?;
+        self.bus.write(self.address, &[LED0_OFF_H + 4*channel, off_h[0] | LED_FULL])?;
+
+        Ok(())
+    }
+
+    /// Re-enable a channel previously disabled with `disable`, restoring
+    /// the duty cycle it had before being disabled.
+    pub fn enable(&mut self, channel: u8) -> Result<(), Error<E>> {
+        if channel >= 16 {
+            return Err(Error::InvalidInputData);
+        }
+
+        let mut off_h = [0];
+        self.bus.write_read(self.address, &[LED0_OFF_H + 4*channel], &mut off_h)?;
+        self.bus.write(self.address, &[LED0_OFF_H + 4*channel, off_h[0] & !LED_FULL])?;
+
+        Ok(())
+    }
+
+    /// Set the output mode of the chip
+    /// Options: Open-Drain or Totem pole.
+    /// # Default
+    /// Totem pole
+    /// # Warnings
+    /// - LEDS with built in zener diodes should only be
+    ///   driven in open drain mode.
+    /// - **Untested**
+    pub fn set_output_mode(&mut self, open_drain: bool) -> Result<(), Error<E>> {
+        //Get the old mode2
+        let mut mode = [0];
+        self.bus.write_read(self.address, &[MODE2], &mut mode)?;
+        let mode = mode[0];
+        //If open drain mode
+        if open_drain {
+            //Since mode2::OUTDRV is default 1, if 1
+            if mode & mode2::OUTDRV == mode2::OUTDRV {
+                //Change to 0
+                self.bus.write(self.address, &[MODE2, mode - mode2::OUTDRV])?;
+                info!(target: "PCA9685_events", "Set to Open-Drain");
+            }
+        } else {
+            if mode & mode2::OUTDRV == 0 {
+                //Change to 1
+                self.bus.write(self.address, &[MODE2, mode + mode2::OUTDRV])?;
+                info!(target: "PCA9685_events", "Set to Totem Pole");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Gets the prescale value
+    pub fn get_prescale(&mut self) -> Result<u8, Error<E>> {
+        let mut buf = [0];
+        self.bus.write_read(self.address, &[PRE_SCALE], &mut buf)?;
+
+        Ok(buf[0])
+    }
+
+    /// Set a channel's duty cycle as a percentage of the full period,
+    /// 0.0 - 100.0.
+    pub fn set_channel_duty_percent(&mut self, channel: u8, duty_percent: f32) -> Result<(), Error<E>> {
+        if !(0.0..=100.0).contains(&duty_percent) {
+            return Err(Error::InvalidInputData);
+        }
+
+        let ticks = (duty_percent / 100.0 * 4095.0).round() as u16;
+        if ticks == 0 {
+            //0/0 is a datasheet-forbidden encoding; drive the pin fully low
+            //via the full-OFF bit instead
+            return self.set_channel_full_off(channel);
+        }
+        self.set_channel_on_off(channel, 0, ticks)
+    }
+
+    /// Set a channel's pulse width in microseconds, based on the PWM
+    /// frequency currently in effect.
+    /// # Warnings
+    /// - `set_prescale_fr` (or `set_external_clock`) must have been called
+    ///   at least once so the period is known.
+    pub fn set_channel_pulse_us(&mut self, channel: u8, microseconds: f32) -> Result<(), Error<E>> {
+        let period_us = self.period_us.ok_or(Error::InvalidInputData)?;
+        let time_per_tick_us = period_us / 4096.0;
+        let ticks = (microseconds / time_per_tick_us).round();
+        if !(0.0..=4095.0).contains(&ticks) {
+            return Err(Error::InvalidInputData);
+        }
+
+        if ticks == 0.0 {
+            //0/0 is a datasheet-forbidden encoding; a 0us pulse means "off"
+            //for servo/ESC control, so drive the pin fully low instead
+            return self.set_channel_full_off(channel);
+        }
+        self.set_channel_on_off(channel, 0, ticks as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Core;
+    use crate::test_support::MockI2c;
+
+    fn core() -> Core<MockI2c> {
+        Core::new(0x40, MockI2c::new())
+    }
+
+    #[test]
+    fn duty_percent_zero_routes_to_full_off() {
+        let mut core = core();
+        core.set_channel_duty_percent(0, 0.0).unwrap();
+        assert_eq!(core.bus.regs[crate::LED0_OFF_H as usize], crate::LED_FULL);
+    }
+
+    #[test]
+    fn duty_percent_zero_then_enable_restores_previous_duty_cycle() {
+        let mut core = core();
+        core.set_channel_on_off(0, 0, 300).unwrap();
+
+        // 0% duty routes through `set_channel_full_off`, which must
+        // read-modify-write the FULL bit rather than clobbering the rest
+        // of off[11:8].
+        core.set_channel_duty_percent(0, 0.0).unwrap();
+        core.enable(0).unwrap();
+
+        let off = u16::from(core.bus.regs[crate::LED0_OFF_L as usize])
+            | (u16::from(core.bus.regs[crate::LED0_OFF_H as usize]) << 8);
+        assert_eq!(off, 300);
+    }
+
+    #[test]
+    fn duty_percent_full_scale_hits_max_ticks() {
+        let mut core = core();
+        core.set_channel_duty_percent(0, 100.0).unwrap();
+        let off = u16::from(core.bus.regs[crate::LED0_OFF_L as usize])
+            | (u16::from(core.bus.regs[crate::LED0_OFF_H as usize]) << 8);
+        assert_eq!(off, 4095);
+    }
+
+    #[test]
+    fn duty_percent_out_of_range_errors() {
+        let mut core = core();
+        assert!(core.set_channel_duty_percent(0, -1.0).is_err());
+        assert!(core.set_channel_duty_percent(0, 100.1).is_err());
+    }
+
+    #[test]
+    fn pulse_us_without_known_period_errors() {
+        let mut core = core();
+        assert!(core.set_channel_pulse_us(0, 1500.0).is_err());
+    }
+
+    #[test]
+    fn pulse_us_zero_routes_to_full_off() {
+        let mut core = core();
+        core.update_timing(29); // ~200Hz period, matching the `freq_from_prescale` test in lib.rs
+        core.set_channel_pulse_us(0, 0.0).unwrap();
+        assert_eq!(core.bus.regs[crate::LED0_OFF_H as usize], crate::LED_FULL);
+    }
+
+    #[test]
+    fn pulse_us_out_of_range_errors() {
+        let mut core = core();
+        core.update_timing(29);
+        let period_us = core.period_us.unwrap();
+        assert!(core.set_channel_pulse_us(0, period_us + 1.0).is_err());
+    }
+}
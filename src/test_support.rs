@@ -0,0 +1,40 @@
+//! Shared test fixtures for the `blocking`/`common` unit tests.
+
+use embedded_hal::blocking::i2c::{Write, WriteRead};
+
+/// A minimal I2C mock backed by a flat register file, enough to drive the
+/// register-level logic under test without real hardware.
+pub(crate) struct MockI2c {
+    pub(crate) regs: [u8; 256],
+}
+
+impl MockI2c {
+    pub(crate) fn new() -> Self {
+        MockI2c { regs: [0; 256] }
+    }
+}
+
+impl Write for MockI2c {
+    type Error = ();
+
+    fn write(&mut self, _address: u8, bytes: &[u8]) -> Result<(), ()> {
+        let reg = bytes[0] as usize;
+        for (i, &byte) in bytes[1..].iter().enumerate() {
+            self.regs[reg + i] = byte;
+        }
+        Ok(())
+    }
+}
+
+impl WriteRead for MockI2c {
+    type Error = ();
+
+    fn write_read(&mut self, address: u8, bytes: &[u8], buffer: &mut [u8]) -> Result<(), ()> {
+        if bytes.len() > 1 {
+            self.write(address, bytes)?;
+        }
+        let reg = bytes[0] as usize;
+        buffer.copy_from_slice(&self.regs[reg..reg + buffer.len()]);
+        Ok(())
+    }
+}